@@ -1,40 +1,86 @@
+use std::collections::HashMap;
 use std::rc::Rc;
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
     Atom(String),
     Variable(String),
+    Integer(i64),
     Op(String),
 }
 
+/// A 1-based line/column in the source text, used to locate lex/parse
+/// errors for the user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// A lex or parse failure, carrying the position it was detected at.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub position: Position,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.position.line, self.position.col, self.message
+        )
+    }
+}
+
 pub struct Lexer {
     pos: usize,
+    line: usize,
+    col: usize,
     input: String,
-    next_token: Option<Token>,
+    next_token: Option<(Token, Position)>,
 }
 
 impl Lexer {
     pub fn new(input: String) -> Self {
         Self {
             pos: 0,
+            line: 1,
+            col: 1,
             input,
             next_token: None,
         }
     }
 
+    fn current_position(&self) -> Position {
+        Position {
+            line: self.line,
+            col: self.col,
+        }
+    }
+
     pub fn peek(&self) -> Option<char> {
         self.input.chars().nth(self.pos)
     }
 
     pub fn pop(&mut self) -> Option<char> {
+        let c = self.input.chars().nth(self.pos)?;
         self.pos += 1;
-        self.input.chars().nth(self.pos - 1)
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
     }
 
-    fn pop_token_internal(&mut self) -> Option<Token> {
+    fn pop_token_internal(&mut self) -> Result<Option<(Token, Position)>, ParseError> {
         loop {
+            let start = self.current_position();
             match self.peek() {
-                None => return None,
+                None => return Ok(None),
                 Some(c) => match c {
                     'a'..='z' => {
                         let mut s = String::new();
@@ -42,7 +88,7 @@ impl Lexer {
                             self.pop();
                             s.push(c);
                         }
-                        return Some(Token::Atom(s));
+                        return Ok(Some((Token::Atom(s), start)));
                     }
                     c @ 'A'..='Z' => {
                         let mut s = String::new();
@@ -52,18 +98,48 @@ impl Lexer {
                             self.pop();
                             s.push(c);
                         }
-                        return Some(Token::Variable(s));
+                        return Ok(Some((Token::Variable(s), start)));
                     }
-                    c @ ('(' | ')' | ',' | '.') => {
+                    '0'..='9' => {
+                        let mut s = String::new();
+                        while let Some(c @ '0'..='9') = self.peek() {
+                            self.pop();
+                            s.push(c);
+                        }
+                        return match s.parse() {
+                            Ok(n) => Ok(Some((Token::Integer(n), start))),
+                            Err(e) => Err(ParseError {
+                                position: start,
+                                message: format!("invalid integer literal '{}': {}", s, e),
+                            }),
+                        };
+                    }
+                    c @ ('(' | ')' | ',' | '.' | '+' | '-' | '*' | '/' | '[' | ']' | '|' | '!') => {
                         self.pop();
-                        return Some(Token::Op(c.to_string()));
+                        return Ok(Some((Token::Op(c.to_string()), start)));
                     }
                     ':' => {
                         self.pop();
-                        if let Some('-') = self.pop() {
-                            return Some(Token::Op(":-".to_string()));
-                        } else {
-                            panic!("Expected -");
+                        match self.pop() {
+                            Some('-') => return Ok(Some((Token::Op(":-".to_string()), start))),
+                            found => {
+                                return Err(ParseError {
+                                    position: start,
+                                    message: format!("expected '-' after ':', found {:?}", found),
+                                })
+                            }
+                        }
+                    }
+                    '\\' => {
+                        self.pop();
+                        match self.pop() {
+                            Some('+') => return Ok(Some((Token::Op("\\+".to_string()), start))),
+                            found => {
+                                return Err(ParseError {
+                                    position: start,
+                                    message: format!("expected '+' after '\\', found {:?}", found),
+                                })
+                            }
                         }
                     }
                     '%' => loop {
@@ -76,38 +152,51 @@ impl Lexer {
                     '\n' | ' ' => {
                         self.pop();
                     }
-                    c => panic!("Unexpected char {}", c),
+                    c => {
+                        return Err(ParseError {
+                            position: start,
+                            message: format!("unexpected char '{}'", c),
+                        })
+                    }
                 },
             }
         }
     }
-    pub fn peek_token(&mut self) -> &Option<Token> {
+    pub fn peek_token(&mut self) -> Result<&Option<(Token, Position)>, ParseError> {
         if self.next_token.is_none() {
-            self.next_token = self.pop_token_internal();
+            self.next_token = self.pop_token_internal()?;
         }
-        println!("peek: {:?}", self.next_token);
-        &self.next_token
+        Ok(&self.next_token)
     }
-    pub fn pop_token(&mut self) -> Option<Token> {
+    pub fn pop_token(&mut self) -> Result<Option<(Token, Position)>, ParseError> {
         if self.next_token.is_none() {
-            self.next_token = self.pop_token_internal();
+            self.next_token = self.pop_token_internal()?;
         }
-        println!("pop: {:?}", self.next_token);
-        self.next_token.take()
+        Ok(self.next_token.take())
     }
-    pub fn consume(&mut self, token: Token) -> bool {
-        if self.peek_token() == &Some(token) {
-            self.next_token.take();
-            true
-        } else {
-            false
+    pub fn consume(&mut self, token: Token) -> Result<bool, ParseError> {
+        match self.peek_token()? {
+            Some((t, _)) if *t == token => {
+                self.next_token.take();
+                Ok(true)
+            }
+            _ => Ok(false),
         }
     }
-    pub fn expect(&mut self, token: Token) {
-        if self.peek_token().as_ref() == Some(&token) {
-            self.next_token.take();
-        } else {
-            panic!("Expected {:?}", token);
+    pub fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        match self.peek_token()? {
+            Some((t, _)) if *t == token => {
+                self.next_token.take();
+                Ok(())
+            }
+            Some((found, position)) => Err(ParseError {
+                position: *position,
+                message: format!("expected {:?}, found {:?}", token, found),
+            }),
+            None => Err(ParseError {
+                position: self.current_position(),
+                message: format!("expected {:?}, found end of input", token),
+            }),
         }
     }
 }
@@ -116,14 +205,15 @@ impl Iterator for Lexer {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.pop_token()
+        self.pop_token().ok().flatten().map(|(t, _)| t)
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Node {
     Atom(String),
     Variable(String),
+    Integer(i64),
     Predicate {
         name: String,
         args: Vec<Rc<Node>>,
@@ -135,96 +225,547 @@ pub enum Node {
     True,
 }
 
+/// A variable binding environment built up while proving a query.
+type Subst = HashMap<String, Rc<Node>>;
+
+/// Follows a chain of variable bindings in `subst` until it reaches an
+/// unbound variable or a non-variable term.
+fn walk(term: &Rc<Node>, subst: &Subst) -> Rc<Node> {
+    match &**term {
+        Node::Variable(name) => match subst.get(name) {
+            Some(bound) => walk(bound, subst),
+            None => term.clone(),
+        },
+        _ => term.clone(),
+    }
+}
+
+/// Checks whether `name` appears (after resolving bindings) inside `term`,
+/// used to reject bindings that would create an infinite structure.
+fn occurs(name: &str, term: &Rc<Node>, subst: &Subst) -> bool {
+    let term = walk(term, subst);
+    match &*term {
+        Node::Variable(v) => v == name,
+        Node::Predicate { args, .. } => args.iter().any(|a| occurs(name, a, subst)),
+        _ => false,
+    }
+}
+
+/// Unifies `a` and `b` under `subst`, returning an extended substitution on
+/// success.
+fn unify(a: &Rc<Node>, b: &Rc<Node>, subst: &Subst) -> Option<Subst> {
+    let a = walk(a, subst);
+    let b = walk(b, subst);
+    match (&*a, &*b) {
+        (Node::Atom(x), Node::Atom(y)) if x == y => Some(subst.clone()),
+        (Node::Integer(x), Node::Integer(y)) if x == y => Some(subst.clone()),
+        (Node::True, Node::True) => Some(subst.clone()),
+        (Node::Variable(x), Node::Variable(y)) if x == y => Some(subst.clone()),
+        (Node::Variable(x), _) => {
+            if occurs(x, &b, subst) {
+                None
+            } else {
+                let mut subst = subst.clone();
+                subst.insert(x.clone(), b);
+                Some(subst)
+            }
+        }
+        (_, Node::Variable(y)) => {
+            if occurs(y, &a, subst) {
+                None
+            } else {
+                let mut subst = subst.clone();
+                subst.insert(y.clone(), a);
+                Some(subst)
+            }
+        }
+        (Node::Predicate { name: xn, args: xa }, Node::Predicate { name: yn, args: ya })
+            if xn == yn && xa.len() == ya.len() =>
+        {
+            let mut subst = subst.clone();
+            for (x, y) in xa.iter().zip(ya.iter()) {
+                subst = unify(x, y, &subst)?;
+            }
+            Some(subst)
+        }
+        _ => None,
+    }
+}
+
+/// Renames every variable in `node` to a fresh name unique to this clause
+/// activation, so a candidate clause's variables never capture the query's.
+fn standardize_apart(node: &Rc<Node>, suffix: u64) -> Rc<Node> {
+    fn go(node: &Rc<Node>, suffix: u64, renamed: &mut HashMap<String, String>) -> Rc<Node> {
+        match &**node {
+            Node::Variable(name) => {
+                let fresh = renamed
+                    .entry(name.clone())
+                    .or_insert_with(|| format!("{}#{}", name, suffix))
+                    .clone();
+                Rc::new(Node::Variable(fresh))
+            }
+            Node::Atom(_) | Node::Integer(_) | Node::True => node.clone(),
+            Node::Predicate { name, args } => Rc::new(Node::Predicate {
+                name: name.clone(),
+                args: args.iter().map(|a| go(a, suffix, renamed)).collect(),
+            }),
+            Node::Clause { left, right } => Rc::new(Node::Clause {
+                left: go(left, suffix, renamed),
+                right: right.iter().map(|r| go(r, suffix, renamed)).collect(),
+            }),
+        }
+    }
+    let mut renamed = HashMap::new();
+    go(node, suffix, &mut renamed)
+}
+
+/// Collects the names of every variable occurring in `node`, in the order
+/// they first appear, without duplicates.
+fn collect_vars(node: &Node, vars: &mut Vec<String>) {
+    match node {
+        Node::Variable(name) => {
+            if !vars.contains(name) {
+                vars.push(name.clone());
+            }
+        }
+        Node::Predicate { args, .. } => {
+            for arg in args {
+                collect_vars(arg, vars);
+            }
+        }
+        Node::Clause { left, right } => {
+            collect_vars(left, vars);
+            for goal in right {
+                collect_vars(goal, vars);
+            }
+        }
+        Node::Atom(_) | Node::Integer(_) | Node::True => {}
+    }
+}
+
+/// Fully dereferences `term` under `subst`, rebuilding any compound term so
+/// that every variable it contains is resolved to its bound value.
+fn resolve(term: &Rc<Node>, subst: &Subst) -> Rc<Node> {
+    let term = walk(term, subst);
+    match &*term {
+        Node::Predicate { name, args } => Rc::new(Node::Predicate {
+            name: name.clone(),
+            args: args.iter().map(|arg| resolve(arg, subst)).collect(),
+        }),
+        _ => term,
+    }
+}
+
+/// Evaluates an arithmetic expression tree (`+`/`-`/`*`/`/` predicates of
+/// arity 2 over integers and bound variables) to a ground integer. Returns
+/// `None` if some operand is still unbound or isn't an arithmetic term.
+fn eval_arith(term: &Rc<Node>, subst: &Subst) -> Option<i64> {
+    let term = walk(term, subst);
+    match &*term {
+        Node::Integer(n) => Some(*n),
+        Node::Predicate { name, args } if args.len() == 2 => {
+            let lhs = eval_arith(&args[0], subst)?;
+            let rhs = eval_arith(&args[1], subst)?;
+            match name.as_str() {
+                "+" => lhs.checked_add(rhs),
+                "-" => lhs.checked_sub(rhs),
+                "*" => lhs.checked_mul(rhs),
+                "/" => lhs.checked_div(rhs),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Handles `is/2`, `lt/2`, `gt/2` and `eq/2` specially instead of resolving
+/// them against `clause_list`. Returns `None` if `goal` isn't one of these
+/// builtins, `Some(None)` if it is but fails, `Some(Some(subst))` on success.
+fn try_builtin(goal: &Rc<Node>, subst: &Subst) -> Option<Option<Subst>> {
+    let (name, args) = match &**goal {
+        Node::Predicate { name, args } if args.len() == 2 => (name.as_str(), args),
+        _ => return None,
+    };
+    match name {
+        "is" => Some(match eval_arith(&args[1], subst) {
+            Some(value) => unify(&args[0], &Rc::new(Node::Integer(value)), subst),
+            None => {
+                eprintln!("is/2: right-hand side is not a ground arithmetic expression");
+                None
+            }
+        }),
+        "lt" | "gt" | "eq" => {
+            let operands = (eval_arith(&args[0], subst), eval_arith(&args[1], subst));
+            Some(match operands {
+                (Some(lhs), Some(rhs)) => {
+                    let holds = match name {
+                        "lt" => lhs < rhs,
+                        "gt" => lhs > rhs,
+                        "eq" => lhs == rhs,
+                        _ => unreachable!(),
+                    };
+                    if holds {
+                        Some(subst.clone())
+                    } else {
+                        None
+                    }
+                }
+                _ => {
+                    eprintln!("{}/2: operand is not a ground arithmetic expression", name);
+                    None
+                }
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Whether `goal` is the cut, `!`, written either as the bare atom or as a
+/// 0-arity predicate (clause heads and goals without arguments are parsed
+/// as the latter).
+fn is_cut(goal: &Rc<Node>) -> bool {
+    match &**goal {
+        Node::Atom(name) => name == "!",
+        Node::Predicate { name, args } => name == "!" && args.is_empty(),
+        _ => false,
+    }
+}
+
+/// If `goal` is `\+ Inner` (negation as failure), returns `Inner`.
+fn negation_goal(goal: &Rc<Node>) -> Option<&Rc<Node>> {
+    match &**goal {
+        Node::Predicate { name, args } if name == "\\+" && args.len() == 1 => Some(&args[0]),
+        _ => None,
+    }
+}
+
+/// The outcome of proving a goal list: `Done(true)` means the whole search
+/// should stop (an `on_solution` callback asked to), `Done(false)` means
+/// this branch found no (more) solutions, and `Cut(barrier)` means a `!`
+/// fired and every choice point back up to the clause activation tagged
+/// `barrier` must be discarded without retrying.
+enum Flow {
+    Done(bool),
+    Cut(u64),
+}
+
+/// The associativity shapes Prolog operators can have: `x` marks an operand
+/// that must bind strictly tighter than the operator, `y` an operand that
+/// may bind as loosely as the operator itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpType {
+    Xfx,
+    Xfy,
+    Yfx,
+    Fy,
+    Fx,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OpDef {
+    pub priority: u32,
+    pub op_type: OpType,
+}
+
+/// Maps operator symbols to a priority (1-1200, lower binds tighter) and an
+/// `OpType`, driving the precedence-climbing expression parser. Seeded with
+/// the standard operators; more can be added at runtime via `register`.
+pub struct OperatorTable {
+    infix: HashMap<String, OpDef>,
+    prefix: HashMap<String, OpDef>,
+}
+
+impl OperatorTable {
+    fn standard() -> Self {
+        let mut table = Self {
+            infix: HashMap::new(),
+            prefix: HashMap::new(),
+        };
+        table.register(":-", 1200, OpType::Xfx);
+        table.register(",", 1000, OpType::Xfy);
+        table.register("is", 700, OpType::Xfx);
+        table.register("lt", 700, OpType::Xfx);
+        table.register("gt", 700, OpType::Xfx);
+        table.register("eq", 700, OpType::Xfx);
+        table.register("+", 500, OpType::Yfx);
+        table.register("-", 500, OpType::Yfx);
+        table.register("*", 400, OpType::Yfx);
+        table.register("/", 400, OpType::Yfx);
+        table.register("\\+", 900, OpType::Fy);
+        table
+    }
+    /// Registers `symbol` as an operator with the given priority and
+    /// associativity.
+    pub fn register(&mut self, symbol: &str, priority: u32, op_type: OpType) {
+        let def = OpDef { priority, op_type };
+        match op_type {
+            OpType::Fy | OpType::Fx => {
+                self.prefix.insert(symbol.to_string(), def);
+            }
+            OpType::Xfx | OpType::Xfy | OpType::Yfx => {
+                self.infix.insert(symbol.to_string(), def);
+            }
+        }
+    }
+}
+
+/// Flattens a right-nested chain of `,`/2 predicates (as produced by the
+/// `,` xfy operator) into a clause body's goal list.
+fn flatten_conjunction(node: &Rc<Node>, goals: &mut Vec<Rc<Node>>) {
+    match &**node {
+        Node::Predicate { name, args } if name == "," && args.len() == 2 => {
+            flatten_conjunction(&args[0], goals);
+            flatten_conjunction(&args[1], goals);
+        }
+        _ => goals.push(node.clone()),
+    }
+}
+
 struct Parser {
     lexer: Lexer,
+    operators: OperatorTable,
 }
 impl Parser {
     fn new(lexer: Lexer) -> Self {
-        Parser { lexer }
+        Parser {
+            lexer,
+            operators: OperatorTable::standard(),
+        }
+    }
+    /// Parses the name of a predicate's functor: either a plain atom or one
+    /// of the non-alphabetic operator symbols (`+`, `-`, `*`, `/`, `!`,
+    /// `\+`) used as a functor name, e.g. `+(1, 2)` or a bare `!`. Returns
+    /// `Ok(None)` when the current token can't start a functor at all,
+    /// rather than an error, so callers can use it to detect the end of
+    /// input.
+    fn parse_functor_name(&mut self) -> Result<Option<String>, ParseError> {
+        match self.lexer.peek_token()? {
+            Some((Token::Atom(_), _)) => match self.lexer.pop_token()? {
+                Some((Token::Atom(s), _)) => Ok(Some(s)),
+                _ => unreachable!(),
+            },
+            Some((Token::Op(op), _)) if matches!(op.as_str(), "+" | "-" | "*" | "/" | "!" | "\\+") => {
+                match self.lexer.pop_token()? {
+                    Some((Token::Op(op), _)) => Ok(Some(op)),
+                    _ => unreachable!(),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+    /// Whether the current token could plausibly begin a term, used to
+    /// decide whether a prefix operator actually has an operand following
+    /// it or should be left as a bare 0-arity atom.
+    fn looks_like_term_start(&mut self) -> Result<bool, ParseError> {
+        Ok(match self.lexer.peek_token()? {
+            None => false,
+            Some((Token::Op(op), _)) => !matches!(op.as_str(), ")" | "]" | "," | "." | "|"),
+            _ => true,
+        })
     }
-    fn parse_atom(&mut self) -> Option<Node> {
-        if let Some(Token::Atom(s)) = self.lexer.peek_token() {
-            let s = s.clone();
-            self.lexer.pop_token();
-            Some(Node::Atom(s))
+    /// Parses a bare atom or operator-symbol functor: a compound
+    /// `Predicate` if it's followed by `(args)`, a prefix-operator
+    /// application if it's registered in `operators.prefix` and an operand
+    /// follows, a 0-arity `Predicate` if `in_goal_position` (matching how
+    /// clause heads and goals without arguments have always been
+    /// represented), or otherwise a plain `Atom`. Only called once the
+    /// caller has confirmed a functor-starting token is present.
+    fn parse_atom_or_predicate(
+        &mut self,
+        max_priority: u32,
+        in_goal_position: bool,
+    ) -> Result<Node, ParseError> {
+        let name = self
+            .parse_functor_name()?
+            .expect("caller confirmed a functor token is present");
+        if self.lexer.consume(Token::Op("(".to_string()))? {
+            let args = self.parse_arg_list()?;
+            self.lexer.expect(Token::Op(")".to_string()))?;
+            return Ok(Node::Predicate { name, args });
+        }
+        if let Some(def) = self.operators.prefix.get(&name).copied() {
+            if def.priority <= max_priority && self.looks_like_term_start()? {
+                let right_max = match def.op_type {
+                    OpType::Fy => def.priority,
+                    _ => def.priority.saturating_sub(1),
+                };
+                let operand = self.parse_expr_in(right_max, in_goal_position)?;
+                return Ok(Node::Predicate {
+                    name,
+                    args: vec![Rc::new(operand)],
+                });
+            }
+        }
+        if in_goal_position {
+            Ok(Node::Predicate {
+                name,
+                args: Vec::new(),
+            })
         } else {
-            None
+            Ok(Node::Atom(name))
         }
     }
-    fn parse_term(&mut self) -> Option<Node> {
-        let token = self.lexer.pop_token();
-        match token {
-            Some(Token::Atom(s)) => Some(Node::Atom(s)),
-            Some(Token::Variable(s)) => Some(Node::Variable(s)),
-            _ => {
-                panic!("Expected term but got {:?}", token)
+    /// Parses a single primary term: a variable, integer, parenthesized
+    /// sub-expression, list, or atom/predicate/prefix-operator form. This is
+    /// the operand `parse_expr` climbs infix operators on top of.
+    /// `in_goal_position` carries forward whether a bare, argument-less atom
+    /// here is a clause head/goal (and so should be a 0-arity `Predicate`)
+    /// or an ordinary term (and so should stay a plain `Atom`); it is true
+    /// only along the spine of `:-`/`,` built by the top-level clause parse
+    /// and turns false as soon as a predicate's `(args)` or a list's `[...]`
+    /// is entered.
+    fn parse_primary(&mut self, max_priority: u32, in_goal_position: bool) -> Result<Node, ParseError> {
+        match self.lexer.peek_token()? {
+            Some((Token::Variable(_), _)) => match self.lexer.pop_token()? {
+                Some((Token::Variable(s), _)) => Ok(Node::Variable(s)),
+                _ => unreachable!(),
+            },
+            Some((Token::Integer(_), _)) => match self.lexer.pop_token()? {
+                Some((Token::Integer(n), _)) => Ok(Node::Integer(n)),
+                _ => unreachable!(),
+            },
+            Some((Token::Op(op), _)) if op == "(" => {
+                self.lexer.pop_token()?;
+                let inner = self.parse_expr_in(1200, in_goal_position)?;
+                self.lexer.expect(Token::Op(")".to_string()))?;
+                Ok(inner)
             }
+            Some((Token::Op(op), _)) if op == "[" => {
+                self.lexer.pop_token()?;
+                self.parse_list()
+            }
+            Some((Token::Atom(_), _)) => self.parse_atom_or_predicate(max_priority, in_goal_position),
+            Some((Token::Op(op), _)) if matches!(op.as_str(), "+" | "-" | "*" | "/" | "!" | "\\+") => {
+                self.parse_atom_or_predicate(max_priority, in_goal_position)
+            }
+            Some((token, position)) => Err(ParseError {
+                position: *position,
+                message: format!("expected a term, found {:?}", token),
+            }),
+            None => Err(ParseError {
+                position: self.lexer.current_position(),
+                message: "expected a term, found end of input".to_string(),
+            }),
         }
     }
-    fn parse_predicate(&mut self) -> Option<Node> {
-        if let Node::Atom(name) = self.parse_atom()? {
-            if !self.lexer.consume(Token::Op("(".to_string())) {
-                Some(Node::Predicate {
-                    name,
-                    args: Vec::new(),
-                })
-            } else {
-                let args = self.parse_term_list()?;
-                self.lexer.expect(Token::Op(")".to_string()));
-                Some(Node::Predicate { name, args })
+    /// The name of the infix operator starting at the current token, if
+    /// any.
+    fn peek_infix_operator(&mut self) -> Result<Option<String>, ParseError> {
+        Ok(match self.lexer.peek_token()? {
+            Some((Token::Atom(name), _)) if self.operators.infix.contains_key(name) => {
+                Some(name.clone())
+            }
+            Some((Token::Op(op), _)) if self.operators.infix.contains_key(op) => Some(op.clone()),
+            _ => None,
+        })
+    }
+    /// Precedence-climbing expression parser: parses a primary term, then
+    /// repeatedly absorbs infix operators whose priority (and, per their
+    /// associativity, whose operand priorities) fit within `max_priority`.
+    fn parse_expr(&mut self, max_priority: u32) -> Result<Node, ParseError> {
+        self.parse_expr_in(max_priority, false)
+    }
+    /// As `parse_expr`, additionally threading through whether a bare atom
+    /// reached here is in clause-head/goal position; see `parse_primary`.
+    fn parse_expr_in(&mut self, max_priority: u32, in_goal_position: bool) -> Result<Node, ParseError> {
+        let mut left = self.parse_primary(max_priority, in_goal_position)?;
+        let mut left_priority = 0;
+        while let Some(op_name) = self.peek_infix_operator()? {
+            let def = match self.operators.infix.get(&op_name) {
+                Some(def) => *def,
+                None => break,
+            };
+            if def.priority > max_priority {
+                break;
+            }
+            let (left_max, right_max) = match def.op_type {
+                OpType::Xfx => (def.priority - 1, def.priority - 1),
+                OpType::Xfy => (def.priority - 1, def.priority),
+                OpType::Yfx => (def.priority, def.priority - 1),
+                OpType::Fy | OpType::Fx => break,
+            };
+            if left_priority > left_max {
+                break;
             }
+            self.lexer.pop_token()?;
+            let right = self.parse_expr_in(right_max, in_goal_position)?;
+            left = Node::Predicate {
+                name: op_name,
+                args: vec![Rc::new(left), Rc::new(right)],
+            };
+            left_priority = def.priority;
+        }
+        Ok(left)
+    }
+    /// Parses a comma-separated argument list at priority 999, the standard
+    /// Prolog bound that keeps a bare `,` acting as an argument separator
+    /// rather than being absorbed as the `,`/2 conjunction operator.
+    fn parse_arg_list(&mut self) -> Result<Vec<Rc<Node>>, ParseError> {
+        let mut args = vec![Rc::new(self.parse_expr(999)?)];
+        while self.lexer.consume(Token::Op(",".to_string()))? {
+            args.push(Rc::new(self.parse_expr(999)?));
+        }
+        Ok(args)
+    }
+    /// Parses the contents of a list term after the opening `[` has already
+    /// been consumed: `]`, `H, ..., T]` or `H, ...|Tail]`. Built as nested
+    /// `.`/2 cons cells terminated by the `[]` atom. Elements are parsed at
+    /// priority 999, the same as predicate arguments.
+    fn parse_list(&mut self) -> Result<Node, ParseError> {
+        if self.lexer.consume(Token::Op("]".to_string()))? {
+            return Ok(Node::Atom("[]".to_string()));
+        }
+        let mut items = vec![self.parse_expr(999)?];
+        while self.lexer.consume(Token::Op(",".to_string()))? {
+            items.push(self.parse_expr(999)?);
+        }
+        let tail = if self.lexer.consume(Token::Op("|".to_string()))? {
+            self.parse_expr(999)?
         } else {
-            None
-        }
-    }
-    fn parse_predicate_list(&mut self) -> Option<Vec<Rc<Node>>> {
-        let p = self.parse_predicate()?;
-        let mut plist = vec![Rc::new(p)];
-        while self.lexer.consume(Token::Op(",".to_string())) {
-            let p = self.parse_predicate()?;
-            plist.push(Rc::new(p));
-        }
-        Some(plist)
-    }
-    fn parse_term_list(&mut self) -> Option<Vec<Rc<Node>>> {
-        let p = self.parse_term()?;
-        let mut plist = vec![Rc::new(p)];
-        while self.lexer.consume(Token::Op(",".to_string())) {
-            let p = self.parse_term()?;
-            plist.push(Rc::new(p));
-        }
-        Some(plist)
-    }
-    fn parse_clause(&mut self) -> Option<Node> {
-        if let Some(left) = self.parse_predicate() {
-            if self.lexer.consume(Token::Op(":-".to_string())) {
-                if let Some(right) = self.parse_predicate_list() {
-                    self.lexer.expect(Token::Op(".".to_string()));
-                    Some(Node::Clause {
-                        left: Rc::new(left),
-                        right,
-                    })
-                } else {
-                    panic!("Expected predicate but got {:?}", self.lexer.peek_token())
+            Node::Atom("[]".to_string())
+        };
+        self.lexer.expect(Token::Op("]".to_string()))?;
+        let mut list = tail;
+        for item in items.into_iter().rev() {
+            list = Node::Predicate {
+                name: ".".to_string(),
+                args: vec![Rc::new(item), Rc::new(list)],
+            };
+        }
+        Ok(list)
+    }
+    /// Turns the top-level expression for one clause into the `Clause`/fact
+    /// shape the evaluator expects: a `:-`/2 expression becomes a head plus
+    /// a flattened goal list, anything else is a fact.
+    fn clause_from_expr(expr: Node) -> Node {
+        match expr {
+            Node::Predicate { name, args } if name == ":-" && args.len() == 2 => {
+                let mut goals = Vec::new();
+                flatten_conjunction(&args[1], &mut goals);
+                Node::Clause {
+                    left: args[0].clone(),
+                    right: goals,
                 }
-            } else {
-                self.lexer.expect(Token::Op(".".to_string()));
-                Some(left)
             }
-        } else {
-            None
+            other => other,
         }
     }
-    fn parse(&mut self) -> Vec<Rc<Node>> {
+    /// Returns `Ok(None)` at the end of input rather than an error, so
+    /// callers can use it to detect the end of a clause list.
+    fn parse_clause(&mut self) -> Result<Option<Node>, ParseError> {
+        if self.lexer.peek_token()?.is_none() {
+            return Ok(None);
+        }
+        let expr = self.parse_expr_in(1200, true)?;
+        self.lexer.expect(Token::Op(".".to_string()))?;
+        Ok(Some(Self::clause_from_expr(expr)))
+    }
+    fn parse(&mut self) -> Result<Vec<Rc<Node>>, ParseError> {
         let mut nodes = Vec::new();
-        loop {
-            let node = self.parse_clause();
-            match node {
-                None => break,
-                Some(node) => nodes.push(Rc::new(node)),
-            }
+        while let Some(node) = self.parse_clause()? {
+            nodes.push(Rc::new(node));
         }
-        nodes
+        Ok(nodes)
     }
 }
 
@@ -238,20 +779,122 @@ blue(x0000ff).
         .to_string(),
     );
     let mut parser = Parser::new(lexer);
-    parser.parse()
+    parser.parse().expect("built-in clause list is well-formed")
 }
 
 struct Evaluator {
     clause_list: Vec<Rc<Node>>,
-    query: Node,
+    query: Rc<Node>,
 }
 
 impl Evaluator {
     fn new(clause_list: Vec<Rc<Node>>, query: Node) -> Self {
-        Self { clause_list, query }
+        Self {
+            clause_list,
+            query: Rc::new(query),
+        }
+    }
+    /// Proves `goals` in order under `subst`, trying each clause in
+    /// `clause_list` against the first goal and backtracking into the next
+    /// clause whenever a branch fails to prove the rest. Every time the goal
+    /// list empties out, `on_solution` is called with the substitution
+    /// reached so far; the search stops as soon as it returns `true`.
+    ///
+    /// Each goal carries the `u64` id of the clause activation it was
+    /// introduced by (its "cut barrier"); `!` uses it to discard every
+    /// choice point back up to that activation, per `Flow::Cut`.
+    fn prove(
+        &self,
+        goals: &[(Rc<Node>, u64)],
+        subst: Subst,
+        counter: &mut u64,
+        on_solution: &mut dyn FnMut(&Subst) -> bool,
+    ) -> Flow {
+        let ((goal, barrier), rest) = match goals.split_first() {
+            None => return Flow::Done(on_solution(&subst)),
+            Some(split) => split,
+        };
+        let barrier = *barrier;
+        if is_cut(goal) {
+            return match self.prove(rest, subst, counter, on_solution) {
+                Flow::Done(true) => Flow::Done(true),
+                Flow::Done(false) => Flow::Cut(barrier),
+                cut @ Flow::Cut(_) => cut,
+            };
+        }
+        if let Some(inner) = negation_goal(goal) {
+            *counter += 1;
+            let inner_barrier = *counter;
+            let mut found = false;
+            self.prove(
+                &[(inner.clone(), inner_barrier)],
+                subst.clone(),
+                counter,
+                &mut |_| {
+                    found = true;
+                    true
+                },
+            );
+            return if found {
+                Flow::Done(false)
+            } else {
+                self.prove(rest, subst, counter, on_solution)
+            };
+        }
+        if let Some(result) = try_builtin(goal, &subst) {
+            return match result {
+                Some(new_subst) => self.prove(rest, new_subst, counter, on_solution),
+                None => Flow::Done(false),
+            };
+        }
+        *counter += 1;
+        let activation = *counter;
+        for clause in &self.clause_list {
+            *counter += 1;
+            let renamed = standardize_apart(clause, *counter);
+            let (head, body) = match &*renamed {
+                Node::Clause { left, right } => (left.clone(), right.clone()),
+                Node::Predicate { .. } | Node::Atom(_) => (renamed.clone(), Vec::new()),
+                _ => continue,
+            };
+            let new_subst = match unify(goal, &head, &subst) {
+                Some(new_subst) => new_subst,
+                None => continue,
+            };
+            let mut new_goals: Vec<(Rc<Node>, u64)> =
+                body.into_iter().map(|g| (g, activation)).collect();
+            new_goals.extend(rest.iter().cloned());
+            match self.prove(&new_goals, new_subst, counter, on_solution) {
+                Flow::Done(true) => return Flow::Done(true),
+                Flow::Done(false) => continue,
+                Flow::Cut(b) if b == activation => return Flow::Done(false),
+                cut @ Flow::Cut(_) => return cut,
+            }
+        }
+        Flow::Done(false)
     }
-    fn eval(&self) -> bool {
-        panic!("eval!!");
+    /// Enumerates every solution to `self.query`, each one restricted to the
+    /// variables that appear in the original query and fully resolved to
+    /// ground terms where possible.
+    fn solutions(&self) -> Vec<Subst> {
+        let mut vars = Vec::new();
+        collect_vars(&self.query, &mut vars);
+        let mut results = Vec::new();
+        let mut counter = 0;
+        self.prove(
+            &[(self.query.clone(), 0)],
+            Subst::new(),
+            &mut counter,
+            &mut |subst| {
+                let bindings = vars
+                    .iter()
+                    .map(|name| (name.clone(), resolve(&Rc::new(Node::Variable(name.clone())), subst)))
+                    .collect();
+                results.push(bindings);
+                false
+            },
+        );
+        results
     }
 }
 
@@ -267,11 +910,42 @@ fn main() -> std::io::Result<()> {
         }
         let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
-        if let Some(query) = parser.parse_clause() {
-            println!("query: {:?}", query);
-            let evaluator = Evaluator::new(clause_list.clone(), query);
-            let result = evaluator.eval();
-            println!("result: {:?}", result);
+        let query = match parser.parse_clause() {
+            Ok(Some(query)) => query,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("parse error: {}", e);
+                continue;
+            }
+        };
+        println!("query: {:?}", query);
+        let evaluator = Evaluator::new(clause_list.clone(), query);
+        let solutions = evaluator.solutions();
+        if solutions.is_empty() {
+            println!("false.");
+            continue;
+        }
+        for (i, bindings) in solutions.iter().enumerate() {
+            if bindings.is_empty() {
+                print!("true");
+            } else {
+                let shown = bindings
+                    .iter()
+                    .map(|(name, value)| format!("{} = {:?}", name, value))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                print!("{}", shown);
+            }
+            if i + 1 == solutions.len() {
+                println!(".");
+                break;
+            }
+            println!();
+            let mut more = String::new();
+            std::io::stdin().read_line(&mut more)?;
+            if !more.trim_start().starts_with(';') {
+                break;
+            }
         }
     }
     Ok(())
@@ -285,7 +959,7 @@ mod tests {
         println!("input: {}", input);
         let lexer = Lexer::new(input.to_string());
         let mut parser = Parser::new(lexer);
-        let node = parser.parse();
+        let node = parser.parse().unwrap();
         println!("node: {:?}", node);
         node
     }
@@ -307,6 +981,9 @@ mod tests {
     fn atom(name: &str) -> Rc<Node> {
         Rc::new(crate::Node::Atom(name.to_string()))
     }
+    fn integer(n: i64) -> Rc<Node> {
+        Rc::new(crate::Node::Integer(n))
+    }
     #[test]
     fn parse() {
         assert!(parse_input("eq(a).") == vec![predicate("eq", vec![atom("a")])]);
@@ -366,4 +1043,165 @@ mod tests {
                 ],
         )
     }
+
+    #[test]
+    fn parse_integer_and_arithmetic() {
+        assert!(parse_input("age(27).") == vec![predicate("age", vec![integer(27)])]);
+        assert!(
+            parse_input("is(X, +(Y, 1)).")
+                == vec![predicate(
+                    "is",
+                    vec![variable("X"), predicate("+", vec![variable("Y"), integer(1)])]
+                )]
+        );
+    }
+
+    #[test]
+    fn unify_and_backtrack_over_multiple_facts() {
+        let clause_list = parse_input("parent(tom, bob). parent(tom, liz).");
+        let query = parse_input("parent(tom, X).").remove(0);
+        let evaluator = Evaluator::new(clause_list, (*query).clone());
+        let solutions = evaluator.solutions();
+        assert!(solutions.len() == 2);
+        assert!(solutions[0].get("X") == Some(&atom("bob")));
+        assert!(solutions[1].get("X") == Some(&atom("liz")));
+    }
+
+    #[test]
+    fn prove_conjunctive_rule_by_chaining_two_calls() {
+        let clause_list = parse_input(
+            "parent(tom, bob). parent(bob, ann). \
+             grandparent(X, Y) :- parent(X, Z), parent(Z, Y).",
+        );
+        let query = parse_input("grandparent(tom, Y).").remove(0);
+        let evaluator = Evaluator::new(clause_list, (*query).clone());
+        let solutions = evaluator.solutions();
+        assert!(solutions.len() == 1);
+        assert!(solutions[0].get("Y") == Some(&atom("ann")));
+    }
+
+    #[test]
+    fn eval_arithmetic() {
+        let clause_list = parse_input("age(tom, 27).");
+        let query = parse_input("is(X, +(1, 2)).").remove(0);
+        let evaluator = Evaluator::new(clause_list, (*query).clone());
+        let solutions = evaluator.solutions();
+        assert!(solutions.len() == 1);
+        assert!(solutions[0].get("X") == Some(&integer(3)));
+
+        let clause_list = parse_input("age(tom, 27).");
+        let query = parse_input("lt(1, 2).").remove(0);
+        let evaluator = Evaluator::new(clause_list, (*query).clone());
+        assert!(evaluator.solutions().len() == 1);
+
+        let clause_list = parse_input("age(tom, 27).");
+        let query = parse_input("gt(1, 2).").remove(0);
+        let evaluator = Evaluator::new(clause_list, (*query).clone());
+        assert!(evaluator.solutions().is_empty());
+    }
+
+    #[test]
+    fn cut_commits_to_first_matching_clause() {
+        let clause_list = parse_input(
+            "classify(X, small) :- X lt 10, !. \
+             classify(X, big) :- X gt 100, !. \
+             classify(X, medium).",
+        );
+        let query = parse_input("classify(5, R).").remove(0);
+        let evaluator = Evaluator::new(clause_list, (*query).clone());
+        let solutions = evaluator.solutions();
+        // Without the cut, classify(X, medium) would also match 5.
+        assert!(solutions.len() == 1);
+        assert!(solutions[0].get("R") == Some(&atom("small")));
+    }
+
+    #[test]
+    fn negation_as_failure() {
+        let member_clauses = "member(X, [X|T]). member(X, [H|T]) :- member(X, T).";
+
+        let clause_list = parse_input(member_clauses);
+        let query = parse_input("\\+ member(x, [a, b]).").remove(0);
+        let evaluator = Evaluator::new(clause_list, (*query).clone());
+        let solutions = evaluator.solutions();
+        assert!(solutions.len() == 1);
+        assert!(solutions[0].is_empty());
+
+        let clause_list = parse_input(member_clauses);
+        let query = parse_input("\\+ member(a, [a, b]).").remove(0);
+        let evaluator = Evaluator::new(clause_list, (*query).clone());
+        assert!(evaluator.solutions().is_empty());
+    }
+
+    fn parse_single_term(input: &str) -> Node {
+        let lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer);
+        parser.parse_expr(1200).unwrap()
+    }
+    fn cons(head: Rc<Node>, tail: Rc<Node>) -> Rc<Node> {
+        Rc::new(Node::Predicate {
+            name: ".".to_string(),
+            args: vec![head, tail],
+        })
+    }
+    fn nil() -> Rc<Node> {
+        atom("[]")
+    }
+
+    #[test]
+    fn operator_precedence() {
+        // `*` (400) binds tighter than `+` (500), both yfx.
+        assert!(
+            parse_single_term("1 + 2 * 3")
+                == *predicate("+", vec![integer(1), predicate("*", vec![integer(2), integer(3)])])
+        );
+        // yfx lets same-priority operators chain to the left: (1 - 2) - 3.
+        assert!(
+            parse_single_term("1 - 2 - 3")
+                == *predicate(
+                    "-",
+                    vec![predicate("-", vec![integer(1), integer(2)]), integer(3)]
+                )
+        );
+        // Infix `is`/`+` parse without the old prefix-call syntax.
+        assert!(
+            parse_input("is(X, +(Y, 1)).") == parse_input("X is Y + 1.")
+        );
+    }
+
+    #[test]
+    fn conjunction_flattens_into_goal_list() {
+        assert!(
+            parse_input("a :- b, c, d.")
+                == vec![clause(
+                    predicate("a", Vec::new()),
+                    vec![
+                        predicate("b", Vec::new()),
+                        predicate("c", Vec::new()),
+                        predicate("d", Vec::new()),
+                    ],
+                )]
+        );
+    }
+
+    #[test]
+    fn parse_list_cons() {
+        assert!(parse_single_term("[]") == *nil());
+        assert!(parse_single_term("[a]") == *cons(atom("a"), nil()));
+        assert!(
+            parse_single_term("[a, b|T]")
+                == *cons(atom("a"), cons(atom("b"), variable("T")))
+        );
+        assert!(
+            parse_single_term("[a, b, c]")
+                == *cons(atom("a"), cons(atom("b"), cons(atom("c"), nil())))
+        );
+    }
+
+    #[test]
+    fn parse_error_reports_position() {
+        let lexer = Lexer::new("a :- b(@).".to_string());
+        let mut parser = Parser::new(lexer);
+        let err = parser.parse_clause().unwrap_err();
+        assert!(err.position == Position { line: 1, col: 8 });
+    }
 }